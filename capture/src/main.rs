@@ -1,10 +1,15 @@
 use warp::Filter;
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time;
 use simplelog::*;
 use std::fs::File;
-use std::io::Write;
+use log::error;
+
+use codechat_editor_server::capture::{query_events_route, Event as DbEvent, EventCapture, EventFilter};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Event {
@@ -13,31 +18,92 @@ struct Event {
     content: Option<String>,
 }
 
+impl Event {
+    /// Maps the wire event - whose `content` field doubles as the session/user id,
+    /// per the existing `log_event` contract - onto the persisted `Event` shape.
+    fn into_db_event(self) -> DbEvent {
+        DbEvent {
+            user_id: self.content.clone().unwrap_or_default(),
+            event_type: self.event_type,
+            timestamp: self.timestamp.to_string(),
+            data: self.content,
+        }
+    }
+}
+
+const FLUSH_THRESHOLD: usize = 200;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/* Buffers incoming events and flushes them to Postgres via a `COPY` batch instead
+of one `INSERT` per event, so bursty keystroke traffic doesn't pay a round trip
+per row. Flushes on whichever comes first: the buffer reaching
+`FLUSH_THRESHOLD`, or the `FLUSH_INTERVAL` timer.
+*/
 #[derive(Clone)]
-struct EventStore {
-    events: Arc<Mutex<HashMap<String, Vec<Event>>>>,
+struct BatchedEventWriter {
+    buffer: Arc<Mutex<Vec<DbEvent>>>,
+    capture: Arc<EventCapture>,
 }
 
-impl EventStore {
-    fn new() -> Self {
-        EventStore {
-            events: Arc::new(Mutex::new(HashMap::new())),
-        }
+impl BatchedEventWriter {
+    fn new(capture: Arc<EventCapture>) -> Self {
+        let writer = BatchedEventWriter {
+            buffer: Arc::new(Mutex::new(Vec::new())),
+            capture,
+        };
+        writer.spawn_flush_timer();
+        writer
     }
 
-    fn add_event(&self, session_id: &str, event: Event) {
-        let mut events = self.events.lock().unwrap();
-        events.entry(session_id.to_string()).or_default().push(event.clone());
+    fn spawn_flush_timer(&self) {
+        let buffer = self.buffer.clone();
+        let capture = self.capture.clone();
+        tokio::spawn(async move {
+            let mut ticker = time::interval(FLUSH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                Self::flush(&buffer, &capture).await;
+            }
+        });
+    }
 
-        // Log the event to a file
-        let log_entry = format!("{:?}\n", event);
-        let mut file = File::options().append(true).open("events.log").unwrap();
-        file.write_all(log_entry.as_bytes()).unwrap();
+    /// Flushes the buffer immediately, regardless of `FLUSH_THRESHOLD`/`FLUSH_INTERVAL`.
+    /// Used on graceful shutdown so the last partial batch isn't lost.
+    async fn flush_now(&self) {
+        Self::flush(&self.buffer, &self.capture).await;
     }
 
-    fn get_events(&self, session_id: &str) -> Option<Vec<Event>> {
-        let events = self.events.lock().unwrap();
-        events.get(session_id).cloned()
+    async fn push(&self, event: DbEvent) {
+        let batch = {
+            let mut buffer = self.buffer.lock().await;
+            buffer.push(event);
+            if buffer.len() >= FLUSH_THRESHOLD {
+                Some(std::mem::take(&mut *buffer))
+            } else {
+                None
+            }
+        };
+        if let Some(batch) = batch {
+            Self::flush_batch(&self.capture, batch).await;
+        }
+    }
+
+    async fn flush(buffer: &Arc<Mutex<Vec<DbEvent>>>, capture: &Arc<EventCapture>) {
+        let batch = {
+            let mut buffer = buffer.lock().await;
+            if buffer.is_empty() {
+                return;
+            }
+            std::mem::take(&mut *buffer)
+        };
+        Self::flush_batch(capture, batch).await;
+    }
+
+    async fn flush_batch(capture: &Arc<EventCapture>, batch: Vec<DbEvent>) {
+        let count = batch.len();
+        if let Err(e) = capture.copy_in_events(&batch).await {
+            error!("Failed to flush {} events via COPY: [{}]", count, e);
+        }
     }
 }
 
@@ -53,44 +119,83 @@ async fn main() {
     ])
     .unwrap();
 
-    let store = EventStore::new();
+    let capture = Arc::new(
+        EventCapture::new("config.json")
+            .await
+            .expect("Failed to connect to the Capture database"),
+    );
+    let writer = BatchedEventWriter::new(capture.clone());
+    let query_events = query_events_route(capture.clone());
 
-    let store_filter = warp::any().map(move || store.clone());
+    let capture_filter = warp::any().map(move || capture.clone());
+    let shutdown_writer = writer.clone();
+    let writer_filter = warp::any().map(move || writer.clone());
 
     let log_event = warp::path("log_event")
         .and(warp::post())
         .and(warp::body::json())
-        .and(store_filter.clone())
+        .and(writer_filter)
         .and_then(handle_log_event);
 
     let get_events = warp::path("get_events")
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
-        .and(store_filter)
+        .and(capture_filter)
         .and_then(handle_get_events);
 
-    let routes = log_event.or(get_events);
+    // General-purpose filtered lookup (by author/kind/time range), in addition to
+    // the simpler by-session-id `get_events` above.
+    let routes = log_event.or(get_events).or(query_events);
+
+    let (_addr, server) =
+        warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], 3030), async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to install the ctrl-c signal handler");
+        });
+    server.await;
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    // Flush whatever's still sitting in the buffer so a clean shutdown doesn't
+    // silently drop the last (sub-FLUSH_THRESHOLD, sub-FLUSH_INTERVAL) batch.
+    shutdown_writer.flush_now().await;
 }
 
-async fn handle_log_event(event: Event, store: EventStore) -> Result<impl warp::Reply, warp::Rejection> {
-    if let Some(session_id) = event.content.clone() {
-        store.add_event(&session_id, event);
-        Ok(warp::reply::json(&"Event logged"))
-    } else {
-        Ok(warp::reply::json(&"Missing session_id"))
+async fn handle_log_event(
+    event: Event,
+    writer: BatchedEventWriter,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if event.content.is_none() {
+        return Ok(warp::reply::json(&"Missing session_id"));
     }
+
+    writer.push(event.into_db_event()).await;
+    Ok(warp::reply::json(&"Event logged"))
 }
 
-async fn handle_get_events(params: HashMap<String, String>, store: EventStore) -> Result<impl warp::Reply, warp::Rejection> {
-    if let Some(session_id) = params.get("session_id") {
-        if let Some(events) = store.get_events(session_id) {
-            Ok(warp::reply::json(&events))
-        } else {
+async fn handle_get_events(
+    params: HashMap<String, String>,
+    capture: Arc<EventCapture>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let Some(session_id) = params.get("session_id") else {
+        return Ok(warp::reply::json(&"Missing session_id"));
+    };
+
+    let filter = EventFilter {
+        authors: Some(vec![session_id.clone()]),
+        ..Default::default()
+    };
+
+    match capture.query_events(&filter).await {
+        Ok(events) if events.is_empty() => {
             Ok(warp::reply::json(&"No events found for session_id"))
         }
-    } else {
-        Ok(warp::reply::json(&"Missing session_id"))
+        Ok(events) => Ok(warp::reply::json(&events)),
+        Err(e) => {
+            error!(
+                "Failed to query events for session_id [{}]: [{}]",
+                session_id, e
+            );
+            Ok(warp::reply::json(&"Failed to query events"))
+        }
     }
 }
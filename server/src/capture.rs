@@ -1,12 +1,20 @@
 // src/capture.rs
 
+use deadpool_postgres::{Manager, ManagerConfig, Pool, PoolError, RecyclingMethod};
 use log::{error, info};
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_postgres::{Client, Error as PgError, NoTls};
+use std::time::Duration;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::config::SslMode;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::Error as PgError;
+use warp::Filter;
 
 /*
 The `Event` struct represents an event to be stored in the database.
@@ -27,7 +35,7 @@ let event = Event {
 };
 
  */
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Event {
     pub user_id: String,
     pub event_type: String,
@@ -38,16 +46,28 @@ pub struct Event {
 /* The `Config` struct represents the database connection parameters read from `config.json`.
 
 Fields:
-    - `db_host`: The hostname or IP address of the database server.
+    - `db_host`: The hostname(s) of the database server. May be a comma-separated
+      list (e.g. "primary.db,replica.db") to fail over between hosts in order.
     - `db_user`: The username for the database connection.
     - `db_password`: The password for the database connection.
     - `db_name`: The name of the database.
+    - `pool_max_size`: The maximum number of connections the pool may hold open at once.
+    - `pool_timeout_seconds`: How long a caller waits for a pooled connection before giving up
+      (`deadpool_postgres::Timeouts::wait`), and also the TCP connect timeout for new connections.
+    - `hostaddr`: An optional numeric IPv4/IPv6 address (or comma-separated list matching
+      `db_host` in length) used for the actual connection, skipping DNS resolution of
+      `db_host`. `db_host` is still sent for TLS certificate/auth purposes when both
+      are set, matching libpq's `host`/`hostaddr` pairing.
 
 let config = Config {
     db_host: "localhost".to_string(),
     db_user: "your_db_user".to_string(),
     db_password: "your_db_password".to_string(),
     db_name: "your_db_name".to_string(),
+    pool_max_size: 16,
+    pool_timeout_seconds: 5,
+    ssl: None,
+    hostaddr: None,
 };
 
 */
@@ -57,11 +77,144 @@ pub struct Config {
     pub db_user: String,
     pub db_password: String,
     pub db_name: String,
+    #[serde(default = "default_pool_max_size")]
+    pub pool_max_size: usize,
+    #[serde(default = "default_pool_timeout_seconds")]
+    pub pool_timeout_seconds: u64,
+    #[serde(default)]
+    pub ssl: Option<SslConfig>,
+    #[serde(default)]
+    pub hostaddr: Option<String>,
+}
+
+fn default_pool_max_size() -> usize {
+    16
+}
+
+fn default_pool_timeout_seconds() -> u64 {
+    5
+}
+
+/* The `SslConfig` struct carries the optional TLS material used to connect to a
+PostgreSQL instance that requires encrypted connections. Each certificate/key
+field may be inline PEM, base64-encoded PEM, or a filesystem path to a PEM
+file; `load_pem` tells them apart by an explicit prefix rather than guessing.
+
+Fields:
+    - `mode`: one of "require" (encrypt only, don't verify the server certificate),
+      "verify-ca" (verify the chain but not the hostname), or "verify-full" (verify
+      chain and hostname) - mirrors libpq's `sslmode` semantics.
+    - `ca_cert`: the CA certificate used to verify the server (inline PEM, `base64:`, or path).
+    - `client_cert`: an optional client certificate for mutual TLS (inline PEM, `base64:`, or path).
+    - `client_key`: the private key matching `client_cert` (inline PEM, `base64:`, or path).
+*/
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SslConfig {
+    pub mode: String,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+/// Loads PEM bytes from `value`: inline PEM (detected by a `-----BEGIN` prefix),
+/// base64-encoded PEM (a `base64:` prefix), or, for anything else, a filesystem
+/// path to a PEM file. Unlike sniffing "does this decode as base64", this never
+/// misreads a base64-alphabet-only path as inline content.
+fn load_pem(value: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use base64::Engine;
+    let trimmed = value.trim();
+    if trimmed.starts_with("-----BEGIN") {
+        Ok(trimmed.as_bytes().to_vec())
+    } else if let Some(encoded) = trimmed.strip_prefix("base64:") {
+        Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?)
+    } else {
+        Ok(fs::read(trimmed)?)
+    }
+}
+
+/* The `DbError` enum distinguishes connection-level failures (the connection was
+closed, timed out, or never established) from query-level failures (bad SQL,
+a constraint violation). `insert_event` only retries on the former, since
+retrying a rejected query would just fail the same way again.
+*/
+#[derive(Debug)]
+pub enum DbError {
+    Connection(String),
+    Query(String),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Connection(msg) => write!(f, "database connection error: {}", msg),
+            DbError::Query(msg) => write!(f, "database query error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<PgError> for DbError {
+    fn from(e: PgError) -> Self {
+        // A SQLSTATE code means the server parsed and rejected the query; anything
+        // else (closed socket, IO failure, timeout) is a connection-level problem.
+        if e.code().is_some() {
+            DbError::Query(e.to_string())
+        } else {
+            DbError::Connection(e.to_string())
+        }
+    }
+}
+
+impl From<PoolError> for DbError {
+    fn from(e: PoolError) -> Self {
+        DbError::Connection(e.to_string())
+    }
+}
+
+/* The `EventFilter` struct models a query over the `events` table, following the
+nostr-relay request/subscription convention of an all-optional filter object:
+every field narrows the result set, and an absent field imposes no constraint.
+
+Fields:
+    - `ids`: match events by their row `id`.
+    - `authors`: match events by `user_id`.
+    - `kinds`: match events by `event_type`.
+    - `since`/`until`: restrict `timestamp` to an inclusive range.
+    - `limit`: cap the number of rows returned (defaults to 100, capped at 1000).
+
+Note: `timestamp` is stored as `TEXT` (see `migrations/V1__create_events.sql`), so
+`since`/`until` and the `ORDER BY timestamp ASC` in `query_events` compare and sort
+lexicographically rather than numerically. That only matches numeric order while
+every stored timestamp has the same number of digits; once it doesn't, range
+filtering and ordering silently go wrong. Fine for now since callers only ever
+feed it `u64::to_string()`, but a future change (e.g. a numeric/`BIGINT` column)
+would be needed to make this correct across a digit-length rollover.
+*/
+#[derive(Deserialize, Debug, Default)]
+pub struct EventFilter {
+    pub ids: Option<Vec<i32>>,
+    pub authors: Option<Vec<String>>,
+    pub kinds: Option<Vec<String>>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<i64>,
+}
+
+const DEFAULT_QUERY_LIMIT: i64 = 100;
+const MAX_QUERY_LIMIT: i64 = 1000;
+
+/// Versioned schema migrations embedded from `migrations/` at compile time, so the
+/// binary carries its own schema instead of relying on a "make sure this table
+/// exists" note. Run via `embedded::migrations::runner()` in `EventCapture::new`.
+mod embedded {
+    refinery::embed_migrations!("migrations");
 }
 
 /*
 The `EventCapture` struct provides methods to interact with the database.
-It holds a `tokio_postgres::Client` for database operations.
+It holds a `deadpool_postgres::Pool`, handing out a pooled connection per
+operation instead of serializing every call through a single client.
 
 # Usage Example
 
@@ -88,7 +241,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
  */
 pub struct EventCapture {
-    db_client: Arc<Mutex<Client>>,
+    pool: Pool<MakeTlsConnector>,
 }
 
 impl EventCapture {
@@ -108,32 +261,109 @@ impl EventCapture {
         let config_content = fs::read_to_string(config_path)?;
         let config: Config = serde_json::from_str(&config_content)?;
 
-        // Build the connection string for the PostgreSQL database
-        let conn_str = format!(
-            "host={} user={} password={} dbname={}",
-            config.db_host, config.db_user, config.db_password, config.db_name
-        );
-
         println!("Attempting Capture Database COnnection...");
+        let pool = Self::build_pool(&config).await?;
 
-        // Connect to the database asynchronously
-        let (client, connection) = tokio_postgres::connect(&conn_str, NoTls).await?;
-
-        // Spawn a task to manage the database connection in the background
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("Database connection error: [{}]", e);
-            }
-        });
+        // Eagerly acquire a connection so startup fails fast if the database is unreachable,
+        // and run any pending schema migrations before the pool serves inserts/queries.
+        let mut client = pool.get().await?;
+        embedded::migrations::runner().run_async(&mut *client).await?;
 
         info!(
-            "Connected to database [{}] as user [{}]",
-            config.db_name, config.db_user
+            "Connected to database [{}] as user [{}] (pool size {})",
+            config.db_name, config.db_user, config.pool_max_size
         );
 
-        Ok(EventCapture {
-            db_client: Arc::new(Mutex::new(client)),
-        })
+        Ok(EventCapture { pool })
+    }
+
+    /// Builds a fresh connection pool from `config`. Pulled out of `new` as its
+    /// own step so it stays easy to follow.
+    async fn build_pool(config: &Config) -> Result<Pool<MakeTlsConnector>, Box<dyn std::error::Error>> {
+        // Build the connection config via the builder API rather than a libpq-style
+        // string. The builder's `host`/`hostaddr` methods mirror libpq's pairing: each
+        // `host()` call still goes to `pg_config` (and TLS hostname verification) so
+        // `sslmode=verify-full` keeps checking the name the caller actually intended,
+        // while the paired `hostaddr()` is what the TCP connection is made to,
+        // skipping DNS resolution of `db_host`.
+        //
+        // `db_host` may be a comma-separated list of hosts, tried in turn on
+        // connection failure; when `hostaddr` is also set, libpq requires a matching
+        // comma-separated list of numeric addresses, one per host.
+        let hosts: Vec<&str> = config.db_host.split(',').map(str::trim).collect();
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .user(&config.db_user)
+            .password(&config.db_password)
+            .dbname(&config.db_name)
+            .connect_timeout(Duration::from_secs(config.pool_timeout_seconds));
+        for host in &hosts {
+            pg_config.host(host);
+        }
+        if let Some(hostaddr) = &config.hostaddr {
+            let addrs: Vec<&str> = hostaddr.split(',').map(str::trim).collect();
+            if addrs.len() != hosts.len() {
+                return Err(format!(
+                    "db_host lists {} host(s) but hostaddr lists {} address(es); \
+                     libpq requires these lists to match in length",
+                    hosts.len(),
+                    addrs.len()
+                )
+                .into());
+            }
+            for addr in addrs {
+                pg_config.hostaddr(addr.parse()?);
+            }
+        }
+
+        // Build a TLS connector from the optional `ssl` section; falls back to
+        // `SslMode::Disable` (equivalent to `NoTls`) when none is configured so
+        // existing `config.json` files keep working unmodified.
+        let mut tls_builder = TlsConnector::builder();
+        match &config.ssl {
+            Some(ssl) => {
+                pg_config.ssl_mode(match ssl.mode.as_str() {
+                    "require" | "verify-ca" | "verify-full" => SslMode::Require,
+                    other => return Err(format!("unknown ssl mode [{}]", other).into()),
+                });
+                // Match libpq's `sslmode` semantics: "require" only encrypts, it does
+                // not verify the server certificate at all; "verify-ca" verifies the
+                // chain but not the hostname; "verify-full" (native-tls's default)
+                // verifies both.
+                match ssl.mode.as_str() {
+                    "require" => tls_builder.danger_accept_invalid_certs(true),
+                    "verify-ca" => tls_builder.danger_accept_invalid_hostnames(true),
+                    _ => &mut tls_builder,
+                };
+                if let Some(ca_cert) = &ssl.ca_cert {
+                    tls_builder.add_root_certificate(Certificate::from_pem(&load_pem(ca_cert)?)?);
+                }
+                if let (Some(cert), Some(key)) = (&ssl.client_cert, &ssl.client_key) {
+                    let cert_pem = load_pem(cert)?;
+                    let key_pem = load_pem(key)?;
+                    tls_builder.identity(Identity::from_pkcs8(&cert_pem, &key_pem)?);
+                }
+            }
+            None => {
+                pg_config.ssl_mode(SslMode::Disable);
+            }
+        }
+        let connector = MakeTlsConnector::new(tls_builder.build()?);
+
+        // Build a connection pool instead of a single shared client, so concurrent
+        // `insert_event` calls run on separate connections rather than serializing
+        // through one lock. `Verified` has `get()` ping each connection before
+        // handing it out, so a single dropped/dead connection is replaced by the
+        // pool itself instead of surfacing as an error that would otherwise look
+        // like a full outage.
+        let manager_config = ManagerConfig {
+            recycling_method: RecyclingMethod::Verified,
+        };
+        let manager = Manager::from_config(pg_config, connector, manager_config);
+        Ok(Pool::builder(manager)
+            .max_size(config.pool_max_size)
+            .wait_timeout(Some(Duration::from_secs(config.pool_timeout_seconds)))
+            .build()?)
     }
 
     /*
@@ -143,7 +373,7 @@ impl EventCapture {
     - `event`: An `Event` instance containing the event data to insert.
 
     # Returns
-    A `Result` indicating success or containing a `tokio_postgres::Error`.
+    A `Result` indicating success or containing a `DbError`.
 
     # Example
     #[tokio::main]
@@ -161,15 +391,31 @@ impl EventCapture {
         Ok(())
     }
     */
-    pub async fn insert_event(&self, event: Event) -> Result<(), PgError> {
+    pub async fn insert_event(&self, event: Event) -> Result<(), DbError> {
+        match self.try_insert_event(&event).await {
+            Ok(()) => Ok(()),
+            Err(DbError::Connection(msg)) => {
+                // With `RecyclingMethod::Verified`, a fresh `pool.get()` already
+                // weeds out dead connections, so retrying the same operation once
+                // is enough - no need to tear down the rest of the (healthy) pool.
+                error!("Connection error inserting event, retrying once: [{}]", msg);
+                self.try_insert_event(&event).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Acquires a pooled client and runs the insert once, with no retry of its own.
+    async fn try_insert_event(&self, event: &Event) -> Result<(), DbError> {
         // SQL statement to insert the event into the 'events' table
         let stmt = "
             INSERT INTO events (user_id, event_type, timestamp, data)
             VALUES ($1, $2, $3, $4)
         ";
 
-        // Acquire a lock on the database client for thread-safe access
-        let client = self.db_client.lock().await;
+        // Acquire a client from the pool for this insert; each call gets its own
+        // connection instead of serializing through a single shared client.
+        let client = self.pool.get().await?;
 
         // Execute the SQL statement with the event data
         client
@@ -188,25 +434,173 @@ impl EventCapture {
 
         Ok(())
     }
+
+    /*
+    Queries persisted events using an `EventFilter`, translating the populated
+    fields into a parameterized `SELECT ... WHERE` with dynamically appended
+    clauses, joined with `AND`. Absent fields place no constraint on the query.
+
+    # Arguments
+    - `filter`: the `EventFilter` describing which events to return.
+
+    # Returns
+    A `Result` containing the matching events (most recent last) or a `DbError`.
+    */
+    pub async fn query_events(&self, filter: &EventFilter) -> Result<Vec<Event>, DbError> {
+        match self.try_query_events(filter).await {
+            Ok(events) => Ok(events),
+            Err(DbError::Connection(msg)) => {
+                error!("Connection error querying events, retrying once: [{}]", msg);
+                self.try_query_events(filter).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs the query once, with no retry of its own.
+    async fn try_query_events(&self, filter: &EventFilter) -> Result<Vec<Event>, DbError> {
+        let mut clauses: Vec<String> = Vec::new();
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+
+        if let Some(ids) = &filter.ids {
+            params.push(Box::new(ids.clone()));
+            clauses.push(format!("id = ANY(${})", params.len()));
+        }
+        if let Some(authors) = &filter.authors {
+            params.push(Box::new(authors.clone()));
+            clauses.push(format!("user_id = ANY(${})", params.len()));
+        }
+        if let Some(kinds) = &filter.kinds {
+            params.push(Box::new(kinds.clone()));
+            clauses.push(format!("event_type = ANY(${})", params.len()));
+        }
+        if let Some(since) = &filter.since {
+            params.push(Box::new(since.clone()));
+            clauses.push(format!("timestamp >= ${}", params.len()));
+        }
+        if let Some(until) = &filter.until {
+            params.push(Box::new(until.clone()));
+            clauses.push(format!("timestamp <= ${}", params.len()));
+        }
+
+        let mut stmt = "SELECT user_id, event_type, timestamp, data FROM events".to_string();
+        if !clauses.is_empty() {
+            stmt.push_str(" WHERE ");
+            stmt.push_str(&clauses.join(" AND "));
+        }
+        stmt.push_str(" ORDER BY timestamp ASC");
+
+        // Clamp to [1, MAX_QUERY_LIMIT] so a caller-supplied zero or negative
+        // limit can't reach Postgres as `LIMIT 0`/`LIMIT -n` and error the query.
+        let limit = filter
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .clamp(1, MAX_QUERY_LIMIT);
+        params.push(Box::new(limit));
+        stmt.push_str(&format!(" LIMIT ${}", params.len()));
+
+        let client = self.pool.get().await?;
+        let param_refs: Vec<&(dyn ToSql + Sync)> =
+            params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect();
+        let rows = client.query(stmt.as_str(), &param_refs[..]).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| Event {
+                user_id: row.get("user_id"),
+                event_type: row.get("event_type"),
+                timestamp: row.get("timestamp"),
+                data: row.get("data"),
+            })
+            .collect())
+    }
+
+    /*
+    Bulk-inserts `events` via a `COPY events (...) FROM STDIN BINARY`, which is an
+    order of magnitude faster than one `INSERT` per row for bursty traffic (e.g.
+    a batched writer flushing on a size threshold or timer).
+
+    # Arguments
+    - `events`: the batch of events to insert, in any order.
+
+    # Returns
+    A `Result` containing the number of rows copied, or a `DbError`.
+    */
+    pub async fn copy_in_events(&self, events: &[Event]) -> Result<u64, DbError> {
+        match self.try_copy_in_events(events).await {
+            Ok(rows) => Ok(rows),
+            Err(DbError::Connection(msg)) => {
+                error!("Connection error flushing events via COPY, retrying once: [{}]", msg);
+                self.try_copy_in_events(events).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs the `COPY` once, with no retry of its own.
+    async fn try_copy_in_events(&self, events: &[Event]) -> Result<u64, DbError> {
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let client = self.pool.get().await?;
+        let sink = client
+            .copy_in("COPY events (user_id, event_type, timestamp, data) FROM STDIN BINARY")
+            .await?;
+        let writer = BinaryCopyInWriter::new(sink, &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT]);
+        tokio::pin!(writer);
+
+        for event in events {
+            writer
+                .as_mut()
+                .write(&[&event.user_id, &event.event_type, &event.timestamp, &event.data])
+                .await?;
+        }
+
+        let rows = writer.finish().await?;
+        info!("Flushed {} events into the database via COPY", rows);
+
+        Ok(rows)
+    }
+}
+
+/* The `query_events` warp route accepts a JSON `EventFilter` body at
+`POST /query_events` and returns the matching events as JSON, so persisted
+capture data can be replayed for analysis and session reconstruction instead
+of only ever being written.
+*/
+pub fn query_events_route(
+    capture: Arc<EventCapture>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let capture_filter = warp::any().map(move || capture.clone());
+
+    warp::path("query_events")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(capture_filter)
+        .and_then(handle_query_events)
+}
+
+async fn handle_query_events(
+    filter: EventFilter,
+    capture: Arc<EventCapture>,
+) -> Result<impl warp::Reply, std::convert::Infallible> {
+    match capture.query_events(&filter).await {
+        Ok(events) => Ok(warp::reply::json(&events)),
+        Err(e) => {
+            error!("Failed to query events: [{}]", e);
+            Ok(warp::reply::json(&serde_json::json!({ "error": e.to_string() })))
+        }
+    }
 }
 
 /*
 Database Schema (SQL DDL)
 
-The following SQL statement creates the `events` table used by this library:
-
-CREATE TABLE events (
-    id SERIAL PRIMARY KEY,
-    user_id TEXT NOT NULL,
-    event_type TEXT NOT NULL,
-    timestamp TEXT NOT NULL,
-    data TEXT
-);
-
-- **`id SERIAL PRIMARY KEY`**: Auto-incrementing primary key.
-- **`user_id TEXT NOT NULL`**: The ID of the user associated with the event.
-- **`event_type TEXT NOT NULL`**: The type of event.
-- **`timestamp TEXT NOT NULL`**: The timestamp of the event.
-- **`data TEXT`**: Optional additional data associated with the event.
-**Note:** Ensure this table exists in your PostgreSQL database before using the library.
+The `events` table used by this library is no longer a "make sure this exists"
+note: it's created automatically by the versioned migrations embedded from
+`migrations/` (see `embedded` above), which `EventCapture::new` runs before
+serving any insert or query. See `migrations/V1__create_events.sql` for the
+table definition and `migrations/V2__index_events_lookup_columns.sql` for the
+lookup indexes. Add new migration files there for future schema changes.
 */